@@ -1,17 +1,87 @@
 use std::fmt;
+use std::error::Error;
 use strings;
 use std::string::String;
 use std::io::prelude::*;
+use std::io::SeekFrom;
 use byteorder::{ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
-use crc::{Hasher32, crc32};
+use encoding_rs::Encoding;
+
+// ERROR
+
+#[derive(Debug)]
+pub enum InnoError {
+	Io(std::io::Error),
+	BadCrc { expected: u32, actual: u32 },
+	UnsupportedVersion(i32),
+	BadHeaderId(String),
+	UnknownRecordType(u16),
+	RecordTooLarge(usize),
+	Malformed(&'static str),
+}
+
+impl fmt::Display for InnoError {
+	fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			InnoError::Io(e) => write!(formatter, "io error: {}", e),
+			InnoError::BadCrc { expected, actual } => write!(
+				formatter,
+				"header crc32 check failed: expected 0x{:x}, got 0x{:x}",
+				expected, actual
+			),
+			InnoError::UnsupportedVersion(v) => write!(formatter, "header version not supported: {}", v),
+			InnoError::BadHeaderId(id) => write!(formatter, "header id not valid: {}", id),
+			InnoError::UnknownRecordType(t) => write!(formatter, "unknown file rec typ: 0x{:x}", t),
+			InnoError::RecordTooLarge(size) => write!(formatter, "file rec data size too large: {}", size),
+			InnoError::Malformed(msg) => write!(formatter, "malformed input: {}", msg),
+		}
+	}
+}
+
+impl Error for InnoError {}
+
+impl From<std::io::Error> for InnoError {
+	fn from(e: std::io::Error) -> InnoError {
+		InnoError::Io(e)
+	}
+}
 
 // HEADER
 
+// Shared IEEE CRC32 helper used by both Header::from_reader and Header::to_writer.
+fn crc32_ieee(data: &[u8]) -> u32 {
+	crc32fast::hash(data)
+}
+
 const HEADER_SIZE: usize = 448;
 const HEADER_ID_32: &str = "Inno Setup Uninstall Log (b)";
 const HEADER_ID_64: &str = "Inno Setup Uninstall Log (b) 64-bit";
+const HEADER_ID_32_ANSI: &str = "Inno Setup Uninstall Log (a)";
+const HEADER_ID_64_ANSI: &str = "Inno Setup Uninstall Log (a) 64-bit";
 const HIGHEST_SUPPORTED_VERSION: i32 = 1048;
 
+// Newer ("(b)") logs are always Unicode; older ("(a)") logs are Ansi and carry the codepage.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LogEncoding {
+	Unicode,
+	Ansi(u32),
+}
+
+fn encoding_for_codepage(codepage: u32) -> &'static Encoding {
+	match codepage {
+		1250 => encoding_rs::WINDOWS_1250,
+		1251 => encoding_rs::WINDOWS_1251,
+		1252 => encoding_rs::WINDOWS_1252,
+		1253 => encoding_rs::WINDOWS_1253,
+		1254 => encoding_rs::WINDOWS_1254,
+		932 => encoding_rs::SHIFT_JIS,
+		936 => encoding_rs::GBK,
+		949 => encoding_rs::EUC_KR,
+		950 => encoding_rs::BIG5,
+		_ => encoding_rs::WINDOWS_1252,
+	}
+}
+
 pub struct Header {
 	id: String,       // 64 bytes
 	app_id: String,   // 128
@@ -20,6 +90,7 @@ pub struct Header {
 	pub num_recs: usize,
 	end_offset: u32,
 	flags: u32,
+	encoding: LogEncoding,
 	crc: u32,
 }
 
@@ -35,6 +106,7 @@ version: {}
 num recs: {}
 end offset: {}
 flags: 0x{:x}
+encoding: {:?}
 crc: 0x{:x}",
 			self.id,
 			self.app_id,
@@ -43,48 +115,65 @@ crc: 0x{:x}",
 			self.num_recs,
 			self.end_offset,
 			self.flags,
+			self.encoding,
 			self.crc,
 		)
 	}
 }
 
+fn write_fixed_string(writer: &mut Write, s: &str, size: usize) -> Result<(), InnoError> {
+	let bytes = s.as_bytes();
+	if bytes.len() > size {
+		return Err(InnoError::Malformed("string too long for field"));
+	}
+	writer.write_all(bytes)?;
+	writer.write_all(&vec![0u8; size - bytes.len()])?;
+	Ok(())
+}
+
 impl Header {
-	pub fn from_reader(reader: &mut Read) -> Header {
+	pub fn from_reader(reader: &mut Read) -> Result<Header, InnoError> {
 		let mut buf = [0; HEADER_SIZE];
-		reader.read_exact(&mut buf).expect("read error");
+		reader.read_exact(&mut buf)?;
 		let mut read: &[u8] = &buf;
 
-		let id = strings::read_utf8_string(&mut read, 64).expect("header id");
-		let app_id = strings::read_utf8_string(&mut read, 128).expect("header app id");
-		let app_name = strings::read_utf8_string(&mut read, 128).expect("header app name");
-		let version = read.read_i32::<LittleEndian>().expect("header version");
-		let num_recs = read.read_i32::<LittleEndian>().expect("header num recs") as usize;
-		let end_offset = read.read_u32::<LittleEndian>().expect("header end offset");
-		let flags = read.read_u32::<LittleEndian>().expect("header flags");
+		let id = strings::read_utf8_string(&mut read, 64)
+			.map_err(|_| InnoError::Malformed("header id"))?;
+		let app_id = strings::read_utf8_string(&mut read, 128)
+			.map_err(|_| InnoError::Malformed("header app id"))?;
+		let app_name = strings::read_utf8_string(&mut read, 128)
+			.map_err(|_| InnoError::Malformed("header app name"))?;
+		let version = read.read_i32::<LittleEndian>()?;
+		let num_recs = read.read_i32::<LittleEndian>()? as usize;
+		let end_offset = read.read_u32::<LittleEndian>()?;
+		let flags = read.read_u32::<LittleEndian>()?;
 
 		let mut reserved = [0; 108];
-		read.read_exact(&mut reserved).expect("header reserved");
-		let crc = read.read_u32::<LittleEndian>().expect("header crc");
+		read.read_exact(&mut reserved)?;
+		let crc = read.read_u32::<LittleEndian>()?;
 
-		let mut digest = crc32::Digest::new(crc32::IEEE);
-		digest.write(&buf[..HEADER_SIZE - 4]);
-		let actual_crc = digest.sum32();
+		let actual_crc = crc32_ieee(&buf[..HEADER_SIZE - 4]);
 
 		if actual_crc != crc {
-			panic!("header crc32 check failed");
+			return Err(InnoError::BadCrc {
+				expected: crc,
+				actual: actual_crc,
+			});
 		}
 
-		match id.as_ref() {
-			HEADER_ID_32 => (),
-			HEADER_ID_64 => (),
-			_ => panic!("header id not valid"),
-		}
+		let encoding = match id.as_ref() {
+			HEADER_ID_32 => LogEncoding::Unicode,
+			HEADER_ID_64 => LogEncoding::Unicode,
+			HEADER_ID_32_ANSI => LogEncoding::Ansi(LittleEndian::read_u32(&reserved[..4])),
+			HEADER_ID_64_ANSI => LogEncoding::Ansi(LittleEndian::read_u32(&reserved[..4])),
+			_ => return Err(InnoError::BadHeaderId(id)),
+		};
 
 		if version > HIGHEST_SUPPORTED_VERSION {
-			panic!("header version not supported");
+			return Err(InnoError::UnsupportedVersion(version));
 		}
 
-		Header {
+		Ok(Header {
 			id,
 			app_id,
 			app_name,
@@ -92,13 +181,70 @@ impl Header {
 			num_recs,
 			end_offset,
 			flags,
+			encoding,
 			crc,
+		})
+	}
+
+	pub fn encoding(&self) -> LogEncoding {
+		self.encoding
+	}
+
+	pub fn to_writer(&mut self, writer: &mut Write) -> Result<(), InnoError> {
+		let mut buf = [0u8; HEADER_SIZE];
+		{
+			let mut w: &mut [u8] = &mut buf[..HEADER_SIZE - 4];
+			write_fixed_string(&mut w, &self.id, 64)?;
+			write_fixed_string(&mut w, &self.app_id, 128)?;
+			write_fixed_string(&mut w, &self.app_name, 128)?;
+			w.write_i32::<LittleEndian>(self.version)?;
+			w.write_i32::<LittleEndian>(self.num_recs as i32)?;
+			w.write_u32::<LittleEndian>(self.end_offset)?;
+			w.write_u32::<LittleEndian>(self.flags)?;
+
+			let mut reserved = [0u8; 108];
+			if let LogEncoding::Ansi(codepage) = self.encoding {
+				LittleEndian::write_u32(&mut reserved[..4], codepage);
+			}
+			w.write_all(&reserved)?;
 		}
+
+		self.crc = crc32_ieee(&buf[..HEADER_SIZE - 4]);
+		(&mut buf[HEADER_SIZE - 4..]).write_u32::<LittleEndian>(self.crc)?;
+
+		writer.write_all(&buf)?;
+		Ok(())
+	}
+}
+
+// Fixes up num_recs and end_offset on header to match records, then writes header + records.
+pub fn write_log(header: &mut Header, records: &[FileRec], writer: &mut Write) -> Result<(), InnoError> {
+	header.num_recs = records.len();
+
+	let mut end_offset = HEADER_SIZE as u32;
+	for rec in records {
+		end_offset += (2 + 4 + 4 + rec.data.len()) as u32;
 	}
+	header.end_offset = end_offset;
+
+	header.to_writer(writer)?;
+	for rec in records {
+		rec.to_writer(writer)?;
+	}
+	writer.flush()?;
+
+	Ok(())
 }
 
 // FILE REC
 
+// Which form a record's string payload was found in, so rebase can re-emit it in the same form.
+#[derive(Debug)]
+enum StringForm {
+	Unicode { old_size: usize },
+	Ansi { codepage: u32 },
+}
+
 #[derive(Copy, Clone)]
 pub enum UninstallRecTyp {
 	UserDefined = 0x01,
@@ -121,8 +267,8 @@ pub enum UninstallRecTyp {
 }
 
 impl UninstallRecTyp {
-	fn from(i: u16) -> UninstallRecTyp {
-		match i {
+	fn from(i: u16) -> Result<UninstallRecTyp, InnoError> {
+		Ok(match i {
 			0x01 => UninstallRecTyp::UserDefined,
 			0x10 => UninstallRecTyp::StartInstall,
 			0x11 => UninstallRecTyp::EndInstall,
@@ -140,11 +286,130 @@ impl UninstallRecTyp {
 			0x8A => UninstallRecTyp::DecrementSharedCount,
 			0x8B => UninstallRecTyp::RefreshFileAssoc,
 			0x8C => UninstallRecTyp::MutexCheck,
-			_ => panic!(""),
-		}
+			_ => return Err(InnoError::UnknownRecordType(i)),
+		})
 	}
 }
 
+// RECORD FIELDS
+//
+// Some record types carry more than one field in their data (e.g. Run's parameters,
+// DeleteDirOrFiles' flags). Fields are self-delimiting so they can be walked in
+// order: Utf16String uses the existing 0xfe-marker form; AnsiString is prefixed with a
+// little-endian u32 byte count instead, since it has no terminator of its own.
+
+// One field of a decoded record payload, in the order it appears in data.
+#[derive(Debug, Clone)]
+pub enum RecordField {
+	Utf16String(String),
+	AnsiString(String),
+	U32(u32),
+	Raw(Vec<u8>),
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum FieldKind {
+	Str,
+}
+
+// The known field layout for a record type, or None if the type's payload shape isn't
+// understood (or isn't trusted). `rebase` leaves records of unknown layout untouched rather
+// than guess.
+//
+// Run, DeleteDirOrFiles, IniDeleteEntry, and RegDeleteValue are genuinely multi-field records,
+// but their field counts/order below have not been checked against real `unins*.dat` output
+// (e.g. via `innounp`) or Inno Setup's own source. Until that validation happens, they're left
+// as None: rebase skips them rather than risk miswriting a field boundary in a live uninstall
+// log. Re-enable each only after confirming its layout and adding a round-trip test against a
+// real sample.
+fn field_layout(typ: UninstallRecTyp) -> Option<&'static [FieldKind]> {
+	use UninstallRecTyp::*;
+	match typ {
+		StartInstall | EndInstall | CompiledCode => None,
+		Run => None,               // unverified: filename, parameters, working dir, run-once flags?
+		DeleteDirOrFiles => None,  // unverified: path, flags?
+		IniDeleteEntry => None,    // unverified: filename, section, key?
+		RegDeleteValue => None,    // unverified: key, value name?
+		UserDefined | DeleteFile | DeleteGroupOrItem | IniDeleteSection | RegDeleteEntireKey
+		| RegClearValue | RegDeleteKeyIfEmpty | DecrementSharedCount | RefreshFileAssoc
+		| MutexCheck => Some(&[FieldKind::Str]),
+	}
+}
+
+fn read_unicode_string(data: &[u8]) -> Result<(String, usize), InnoError> {
+	if data.len() < 5 || data[0] != 0xfe {
+		return Err(InnoError::Malformed("unicode string marker"));
+	}
+
+	let size = LittleEndian::read_i32(&data[1..5]);
+	if size >= 0 {
+		return Err(InnoError::Malformed("unicode string size"));
+	}
+
+	let old_size = -size as usize;
+	if old_size % 2 != 0 || data.len() < 5 + old_size + 1 {
+		return Err(InnoError::Malformed("unicode string too short"));
+	}
+	if data[5 + old_size] != 0xff {
+		return Err(InnoError::Malformed("unicode string terminator"));
+	}
+
+	let mut u16data: Vec<u16> = vec![0; old_size / 2];
+	LittleEndian::read_u16_into(&data[5..5 + old_size], &mut u16data);
+
+	let s = String::from_utf16(&u16data).map_err(|_| InnoError::Malformed("unicode string data"))?;
+	Ok((s, 5 + old_size + 1))
+}
+
+fn write_unicode_string(s: &str) -> Vec<u8> {
+	let u16data: Vec<u16> = s.encode_utf16().collect();
+	let size = u16data.len() * 2;
+
+	let mut out = vec![0u8; 5 + size + 1];
+	out[0] = 0xfe;
+	LittleEndian::write_i32(&mut out[1..5], -(size as i32));
+	LittleEndian::write_u16_into(&u16data, &mut out[5..5 + size]);
+	out[5 + size] = 0xff;
+	out
+}
+
+// The u32-length-prefix framing below is this crate's own invention for delimiting an ANSI
+// string inside a multi-field record's data; it isn't cross-checked against a real multi-field
+// ANSI record (e.g. an ANSI `Run` entry). Currently unreachable in practice, since every
+// field_layout entry with a Str field is single-field (see field_layout) and goes through
+// get_string's whole-buffer ANSI form instead. Don't wire a multi-field layout back up to this
+// framing without validating it against a real sample first.
+fn read_ansi_string(data: &[u8], codepage: u32) -> Result<(String, usize), InnoError> {
+	if data.len() < 4 {
+		return Err(InnoError::Malformed("ansi string length"));
+	}
+	let len = LittleEndian::read_u32(&data[..4]) as usize;
+	if data.len() < 4 + len {
+		return Err(InnoError::Malformed("ansi string too short"));
+	}
+
+	let (decoded, _, had_errors) = encoding_for_codepage(codepage).decode(&data[4..4 + len]);
+	if had_errors {
+		return Err(InnoError::Malformed("ansi string data"));
+	}
+
+	Ok((decoded.into_owned(), 4 + len))
+}
+
+fn write_ansi_string(s: &str, codepage: u32) -> Result<Vec<u8>, InnoError> {
+	let (encoded, _, had_errors) = encoding_for_codepage(codepage).encode(s);
+	if had_errors {
+		return Err(InnoError::Malformed("path not representable in codepage"));
+	}
+
+	let mut out = Vec::with_capacity(4 + encoded.len());
+	let mut len_buf = [0u8; 4];
+	LittleEndian::write_u32(&mut len_buf, encoded.len() as u32);
+	out.extend_from_slice(&len_buf);
+	out.extend_from_slice(&encoded);
+	Ok(out)
+}
+
 pub struct FileRec {
 	pub typ: UninstallRecTyp,
 	extra_data: u32,
@@ -164,93 +429,554 @@ impl<'a> fmt::Debug for FileRec {
 }
 
 impl<'a> FileRec {
-	pub fn from_reader(reader: &mut Read) -> FileRec {
-		let typ = reader.read_u16::<LittleEndian>().expect("file rec typ");
-		let extra_data = reader
-			.read_u32::<LittleEndian>()
-			.expect("file rec extra data");
-		let data_size = reader
-			.read_u32::<LittleEndian>()
-			.expect("file rec data size") as usize;
+	pub fn from_reader(reader: &mut Read) -> Result<FileRec, InnoError> {
+		let typ = reader.read_u16::<LittleEndian>()?;
+		let extra_data = reader.read_u32::<LittleEndian>()?;
+		let data_size = reader.read_u32::<LittleEndian>()? as usize;
 
 		if data_size > 0x8000000 {
-			panic!("file rec data size too large {}", data_size);
+			return Err(InnoError::RecordTooLarge(data_size));
 		}
 
 		let mut data = vec![0; data_size];
-		reader.read_exact(&mut data).expect("file rec data");
+		reader.read_exact(&mut data)?;
 
-		let typ = UninstallRecTyp::from(typ);
+		let typ = UninstallRecTyp::from(typ)?;
 
-		FileRec {
+		Ok(FileRec {
 			typ,
 			extra_data,
 			data,
+		})
+	}
+
+	pub fn to_writer(&self, writer: &mut Write) -> Result<(), InnoError> {
+		writer.write_u16::<LittleEndian>(self.typ as u16)?;
+		writer.write_u32::<LittleEndian>(self.extra_data)?;
+		writer.write_u32::<LittleEndian>(self.data.len() as u32)?;
+		writer.write_all(&self.data)?;
+		Ok(())
+	}
+
+	fn get_string(&self, header_encoding: LogEncoding) -> Result<(String, StringForm), InnoError> {
+		if self.data.is_empty() {
+			return Err(InnoError::Malformed("file rec data empty"));
+		}
+
+		// A `Unicode` log's records are always 0xfe-marked; a record that isn't is corrupt,
+		// not an ANSI string in disguise. `encoding_rs`'s single-byte decoders accept almost
+		// any byte sequence, so without this check corruption would silently decode as
+		// garbage text instead of surfacing as `Malformed`.
+		if header_encoding == LogEncoding::Unicode && self.data[0] != 0xfe {
+			return Err(InnoError::Malformed("file rec data first byte"));
+		}
+
+		if self.data[0] == 0xfe {
+			let mut read_slice: &[u8] = &self.data;
+			let reader: &mut Read = &mut read_slice;
+
+			reader.read_u8()?;
+
+			let size = reader.read_i32::<LittleEndian>()?;
+			if size >= 0 {
+				return Err(InnoError::Malformed("file rec data size"));
+			}
+
+			let slice: &[u8] = &self.data;
+			if slice[slice.len() - 1] != 0xff {
+				return Err(InnoError::Malformed("file rec data last byte"));
+			}
+
+			let old_size = -size as usize;
+			if old_size % 2 != 0 {
+				return Err(InnoError::Malformed("file rec data size not even"));
+			}
+			if slice.len() < 5 + old_size {
+				return Err(InnoError::Malformed("file rec data too short"));
+			}
+
+			let mut u16data: Vec<u16> = vec![0; old_size / 2];
+
+			LittleEndian::read_u16_into(&slice[5..5 + old_size], &mut u16data);
+
+			Ok((
+				String::from_utf16(&u16data).map_err(|_| InnoError::Malformed("file rec data string"))?,
+				StringForm::Unicode { old_size },
+			))
+		} else {
+			let codepage = match header_encoding {
+				LogEncoding::Ansi(codepage) => codepage,
+				LogEncoding::Unicode => unreachable!("checked above"),
+			};
+			let encoding = encoding_for_codepage(codepage);
+			let (decoded, _, had_errors) = encoding.decode(&self.data);
+			if had_errors {
+				return Err(InnoError::Malformed("file rec ansi data"));
+			}
+
+			Ok((decoded.into_owned(), StringForm::Ansi { codepage }))
 		}
 	}
 
-	fn get_string(&self) -> (String, usize) {
-		let mut read_slice: &[u8] = &self.data;
-		let reader: &mut Read = &mut read_slice;
+	fn rebase_whole_string(&mut self, from: &str, to: &str, header_encoding: LogEncoding) -> Result<(), InnoError> {
+		let (mut path, form) = self.get_string(header_encoding)?;
 
-		let first = reader.read_u8().expect("file rec data first byte");
-		assert!(first == 0xfe);
+		if path.starts_with(from) {
+			path = [to, &path[from.len()..]].join("");
+		}
 
-		let size = reader
-			.read_i32::<LittleEndian>()
-			.expect("file rec data size");
-		assert!(size < 0);
+		match form {
+			StringForm::Unicode { old_size } => {
+				let u16data: Vec<u16> = path.encode_utf16().collect();
+				let new_size = u16data.len() * 2;
+				let mut data: Vec<u8> = vec![0; self.data.len() - old_size + new_size];
+
+				{
+					let mut slice: &mut [u8] = &mut data[..];
+					let writer: &mut Write = &mut slice;
+
+					writer.write_u8(0xfe)?;
+					writer.write_i32::<LittleEndian>(-(new_size as i32))?;
+				}
+
+				{
+					let slice = &mut data[5..5 + new_size];
+					LittleEndian::write_u16_into(&u16data, slice);
+				}
+
+				{
+					let old_rest = &self.data[5 + old_size..];
+					let new_rest = &mut data[5 + new_size..];
+					new_rest.copy_from_slice(old_rest);
+				}
+
+				self.data = data;
+			}
+			StringForm::Ansi { codepage } => {
+				let encoding = encoding_for_codepage(codepage);
+				let (encoded, _, had_errors) = encoding.encode(&path);
+				if had_errors {
+					return Err(InnoError::Malformed("path not representable in codepage"));
+				}
+
+				self.data = encoded.into_owned();
+			}
+		}
 
-		let slice: &[u8] = &self.data;
-		let last = slice[slice.len() - 1];
-		assert!(last == 0xff);
+		Ok(())
+	}
 
-		let old_size = -size as usize;
-		assert!(old_size % 2 == 0);
+	fn decode_fields_with_layout(
+		&self,
+		layout: &[FieldKind],
+		header_encoding: LogEncoding,
+	) -> Result<Vec<RecordField>, InnoError> {
+		let mut fields = Vec::with_capacity(layout.len());
+		let mut cursor = 0usize;
+
+		for kind in layout {
+			match kind {
+				FieldKind::Str => {
+					if cursor >= self.data.len() {
+						return Err(InnoError::Malformed("record field too short"));
+					}
+					// Same rule as `get_string`: a `Unicode` log never has a bare (unmarked)
+					// string field, so a missing 0xfe here means the record is corrupt, not
+					// that this field happens to be ANSI.
+					if header_encoding == LogEncoding::Unicode && self.data[cursor] != 0xfe {
+						return Err(InnoError::Malformed("record field missing unicode marker"));
+					}
+					if self.data[cursor] == 0xfe {
+						let (s, consumed) = read_unicode_string(&self.data[cursor..])?;
+						fields.push(RecordField::Utf16String(s));
+						cursor += consumed;
+					} else {
+						let codepage = match header_encoding {
+							LogEncoding::Ansi(codepage) => codepage,
+							LogEncoding::Unicode => unreachable!("checked above"),
+						};
+						let (s, consumed) = read_ansi_string(&self.data[cursor..], codepage)?;
+						fields.push(RecordField::AnsiString(s));
+						cursor += consumed;
+					}
+				}
+			}
+		}
 
-		let mut u16data: Vec<u16> = vec![0; old_size / 2];
-		// println!("{}, {}, {}", size, 5 + size, self.data.len());
+		if cursor < self.data.len() {
+			fields.push(RecordField::Raw(self.data[cursor..].to_vec()));
+		}
 
-		LittleEndian::read_u16_into(&slice[5..5 + old_size], &mut u16data);
+		Ok(fields)
+	}
 
-		(
-			String::from_utf16(&u16data).expect("file rec data string"),
-			old_size,
-		)
+	// Decodes this record's payload into its typed fields, or None if the layout isn't known
+	// or the payload doesn't decode under it.
+	pub fn decode_fields(&self, header_encoding: LogEncoding) -> Option<Vec<RecordField>> {
+		let layout = field_layout(self.typ)?;
+
+		if layout.len() == 1 && layout[0] == FieldKind::Str {
+			let (s, form) = self.get_string(header_encoding).ok()?;
+			return Some(vec![match form {
+				StringForm::Unicode { .. } => RecordField::Utf16String(s),
+				StringForm::Ansi { .. } => RecordField::AnsiString(s),
+			}]);
+		}
+
+		self.decode_fields_with_layout(layout, header_encoding).ok()
 	}
 
-	pub fn rebase(&mut self, from: &str, to: &str) {
-		let (mut path, old_size) = self.get_string();
+	fn rebase_fields(&mut self, from: &str, to: &str, layout: &[FieldKind], header_encoding: LogEncoding) -> Result<(), InnoError> {
+		let fields = self.decode_fields_with_layout(layout, header_encoding)?;
+
+		let mut data = Vec::with_capacity(self.data.len());
+		for field in fields {
+			match field {
+				RecordField::Utf16String(mut s) => {
+					if s.starts_with(from) {
+						s = [to, &s[from.len()..]].join("");
+					}
+					data.extend(write_unicode_string(&s));
+				}
+				RecordField::AnsiString(mut s) => {
+					if s.starts_with(from) {
+						s = [to, &s[from.len()..]].join("");
+					}
+					let codepage = match header_encoding {
+						LogEncoding::Ansi(codepage) => codepage,
+						LogEncoding::Unicode => 1252,
+					};
+					data.extend(write_ansi_string(&s, codepage)?);
+				}
+				RecordField::U32(v) => {
+					let mut buf = [0u8; 4];
+					LittleEndian::write_u32(&mut buf, v);
+					data.extend_from_slice(&buf);
+				}
+				RecordField::Raw(bytes) => data.extend(bytes),
+			}
+		}
 
-		if path.starts_with(from) {
-			path = [to, &path[from.len()..]].join("");
+		self.data = data;
+		Ok(())
+	}
+
+	pub fn rebase(&mut self, from: &str, to: &str, header_encoding: LogEncoding) -> Result<(), InnoError> {
+		match field_layout(self.typ) {
+			None => Ok(()),
+			Some(layout) if layout.len() == 1 && layout[0] == FieldKind::Str => {
+				self.rebase_whole_string(from, to, header_encoding)
+			}
+			Some(layout) => self.rebase_fields(from, to, layout, header_encoding),
 		}
+	}
+}
 
-		let u16data: Vec<u16> = path.encode_utf16().collect();
-		let new_size = u16data.len() * 2;
-		let mut data: Vec<u8> = vec![0; self.data.len() - old_size + new_size];
+// RECORD READER
 
-		{
-			let mut slice: &mut [u8] = &mut data[..];
-			let writer: &mut Write = &mut slice;
+// Caps reads to `limit` bytes from the current position, so a record claiming a bogus
+// data_size can't read past end_offset.
+struct TakeSeek<R> {
+	inner: R,
+	limit: u64,
+}
+
+impl<R: Read> Read for TakeSeek<R> {
+	fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+		let max = std::cmp::min(buf.len() as u64, self.limit) as usize;
+		let n = self.inner.read(&mut buf[..max])?;
+		self.limit -= n as u64;
+		Ok(n)
+	}
+}
 
-			writer.write_u8(0xfe).expect("file rec data first byte");
-			writer
-				.write_i32::<LittleEndian>(-(new_size as i32))
-				.expect("file rec data size");
+impl<R: Seek> Seek for TakeSeek<R> {
+	fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+		self.inner.seek(pos)
+	}
+}
+
+// Streams the records following a parsed Header without buffering the whole log; errors if
+// the running offset doesn't land on end_offset once num_recs records have been read.
+pub struct RecordReader<R> {
+	reader: TakeSeek<R>,
+	num_recs: usize,
+	end_offset: u64,
+	offset: u64,
+	count: usize,
+	done: bool,
+}
+
+impl<R: Read + Seek> RecordReader<R> {
+	pub fn new(mut reader: R, header: &Header) -> Result<RecordReader<R>, InnoError> {
+		let start_offset = reader.seek(SeekFrom::Current(0))?;
+		let end_offset = header.end_offset as u64;
+
+		if end_offset < start_offset {
+			return Err(InnoError::Malformed("end_offset before current position"));
 		}
 
-		{
-			let slice = &mut data[5..5 + new_size];
-			LittleEndian::write_u16_into(&u16data, slice);
+		Ok(RecordReader {
+			reader: TakeSeek {
+				inner: reader,
+				limit: end_offset - start_offset,
+			},
+			num_recs: header.num_recs,
+			end_offset,
+			offset: start_offset,
+			count: 0,
+			done: false,
+		})
+	}
+}
+
+impl<R: Read + Seek> Iterator for RecordReader<R> {
+	type Item = Result<FileRec, InnoError>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.done {
+			return None;
 		}
 
-		{
-			let old_rest = &self.data[5 + old_size..];
-			let new_rest = &mut data[5 + new_size..];
-			new_rest.copy_from_slice(old_rest);
+		if self.count >= self.num_recs {
+			self.done = true;
+			if self.offset != self.end_offset {
+				return Some(Err(InnoError::Malformed("record count reached but end_offset not met")));
+			}
+			return None;
 		}
 
-		self.data = data;
+		match FileRec::from_reader(&mut self.reader) {
+			Ok(rec) => {
+				self.offset += (2 + 4 + 4 + rec.data.len()) as u64;
+				self.count += 1;
+				Some(Ok(rec))
+			}
+			Err(e) => {
+				self.done = true;
+				Some(Err(e))
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::crc32_ieee;
+	use super::{ByteOrder, HEADER_SIZE};
+	use crc::{crc32, Hasher32};
+
+	fn old_crc32_ieee(data: &[u8]) -> u32 {
+		let mut digest = crc32::Digest::new(crc32::IEEE);
+		digest.write(data);
+		digest.sum32()
+	}
+
+	#[test]
+	fn crc32_ieee_matches_old_crc_crate() {
+		let samples: &[&[u8]] = &[
+			&[],
+			b"Inno Setup Uninstall Log (b)",
+			&[0u8; HEADER_SIZE - 4],
+			&(0..=255).collect::<Vec<u8>>(),
+		];
+
+		for sample in samples {
+			assert_eq!(crc32_ieee(sample), old_crc32_ieee(sample));
+		}
+	}
+
+	fn ansi_rec(data: Vec<u8>) -> super::FileRec {
+		super::FileRec {
+			typ: super::UninstallRecTyp::DeleteFile,
+			extra_data: 0,
+			data,
+		}
+	}
+
+	#[test]
+	fn ansi_string_round_trips_through_rebase() {
+		let (raw, _, _) = encoding_rs::WINDOWS_1252.encode("C:\\Old\\file.txt");
+		let mut rec = ansi_rec(raw.into_owned());
+
+		rec.rebase("C:\\Old", "D:\\New", super::LogEncoding::Ansi(1252)).unwrap();
+
+		let (path, form) = rec.get_string(super::LogEncoding::Ansi(1252)).unwrap();
+		assert_eq!(path, "D:\\New\\file.txt");
+		assert!(matches!(form, super::StringForm::Ansi { codepage: 1252 }));
+	}
+
+	#[test]
+	fn unicode_log_rejects_record_missing_marker() {
+		let rec = ansi_rec(vec![1, 2, 3, 4]);
+		let err = rec.get_string(super::LogEncoding::Unicode).unwrap_err();
+		assert!(matches!(err, super::InnoError::Malformed(_)));
+	}
+
+	fn u32_bytes(v: u32) -> Vec<u8> {
+		let mut buf = [0u8; 4];
+		super::LittleEndian::write_u32(&mut buf, v);
+		buf.to_vec()
+	}
+
+	fn typed_rec(typ: super::UninstallRecTyp, fields: Vec<Vec<u8>>, trailing: Vec<u8>) -> super::FileRec {
+		let mut data = Vec::new();
+		for field in fields {
+			data.extend(field);
+		}
+		data.extend(trailing);
+		super::FileRec { typ, extra_data: 0, data }
+	}
+
+	#[test]
+	fn run_record_is_left_untouched_pending_layout_validation() {
+		let original = vec![
+			super::write_unicode_string("C:\\Old\\app.exe"),
+			super::write_unicode_string("-silent"),
+			super::write_unicode_string("C:\\Old"),
+			u32_bytes(7),
+		]
+		.concat();
+		let mut rec = typed_rec(super::UninstallRecTyp::Run, vec![original.clone()], vec![]);
+
+		rec.rebase("C:\\Old", "D:\\New", super::LogEncoding::Unicode).unwrap();
+
+		assert_eq!(rec.data, original);
+	}
+
+	#[test]
+	fn delete_dir_or_files_record_is_left_untouched_pending_layout_validation() {
+		let original = vec![super::write_unicode_string("C:\\Old\\dir"), u32_bytes(3)].concat();
+		let mut rec = typed_rec(super::UninstallRecTyp::DeleteDirOrFiles, vec![original.clone()], vec![]);
+
+		rec.rebase("C:\\Old", "D:\\New", super::LogEncoding::Unicode).unwrap();
+
+		assert_eq!(rec.data, original);
+	}
+
+	#[test]
+	fn ini_delete_entry_record_is_left_untouched_pending_layout_validation() {
+		let original = vec![
+			super::write_unicode_string("C:\\Old\\settings.ini"),
+			super::write_unicode_string("Section"),
+			super::write_unicode_string("Key"),
+		]
+		.concat();
+		let mut rec = typed_rec(super::UninstallRecTyp::IniDeleteEntry, vec![original.clone()], vec![]);
+
+		rec.rebase("C:\\Old", "D:\\New", super::LogEncoding::Unicode).unwrap();
+
+		assert_eq!(rec.data, original);
+	}
+
+	#[test]
+	fn reg_delete_value_record_is_left_untouched_pending_layout_validation() {
+		let original = vec![
+			super::write_unicode_string("C:\\Old\\subkey"),
+			super::write_unicode_string("ValueName"),
+		]
+		.concat();
+		let mut rec = typed_rec(super::UninstallRecTyp::RegDeleteValue, vec![original.clone()], vec![]);
+
+		rec.rebase("C:\\Old", "D:\\New", super::LogEncoding::Unicode).unwrap();
+
+		assert_eq!(rec.data, original);
+	}
+
+	#[test]
+	fn unknown_layout_record_is_left_untouched() {
+		let original = vec![1, 2, 3, 4, 5];
+		let mut rec = typed_rec(super::UninstallRecTyp::StartInstall, vec![original.clone()], vec![]);
+
+		rec.rebase("C:\\Old", "D:\\New", super::LogEncoding::Unicode).unwrap();
+
+		assert_eq!(rec.data, original);
+	}
+
+	fn sample_header() -> super::Header {
+		super::Header {
+			id: "Inno Setup Uninstall Log (b)".to_string(),
+			app_id: "{SOME-APP-ID}".to_string(),
+			app_name: "Sample App".to_string(),
+			version: 1048,
+			num_recs: 0,
+			end_offset: 0,
+			flags: 0,
+			encoding: super::LogEncoding::Unicode,
+			crc: 0,
+		}
+	}
+
+	#[test]
+	fn write_log_round_trip_fixes_up_num_recs_end_offset_and_crc() {
+		let mut header = sample_header();
+		let records = vec![
+			typed_rec(
+				super::UninstallRecTyp::DeleteFile,
+				vec![super::write_unicode_string("C:\\Old\\file.txt")],
+				vec![],
+			),
+			typed_rec(super::UninstallRecTyp::StartInstall, vec![], vec![]),
+		];
+
+		let mut buf = Vec::new();
+		super::write_log(&mut header, &records, &mut buf).unwrap();
+
+		assert_eq!(header.num_recs, records.len());
+		assert_eq!(header.end_offset as usize, buf.len());
+
+		let mut cursor = std::io::Cursor::new(buf);
+		let read_back = super::Header::from_reader(&mut cursor).unwrap();
+		assert_eq!(read_back.num_recs, records.len());
+
+		for original in &records {
+			let rec = super::FileRec::from_reader(&mut cursor).unwrap();
+			assert_eq!(rec.data, original.data);
+		}
+	}
+
+	#[test]
+	fn record_reader_yields_every_record_and_stops_at_end_offset() {
+		let mut header = sample_header();
+		let records = vec![
+			typed_rec(
+				super::UninstallRecTyp::DeleteFile,
+				vec![super::write_unicode_string("C:\\Old\\file.txt")],
+				vec![],
+			),
+			typed_rec(super::UninstallRecTyp::StartInstall, vec![], vec![]),
+		];
+
+		let mut buf = Vec::new();
+		super::write_log(&mut header, &records, &mut buf).unwrap();
+
+		let mut cursor = std::io::Cursor::new(buf);
+		let read_back = super::Header::from_reader(&mut cursor).unwrap();
+
+		let results: Vec<_> = super::RecordReader::new(cursor, &read_back).unwrap().collect();
+		assert_eq!(results.len(), records.len());
+		for (result, original) in results.into_iter().zip(&records) {
+			assert_eq!(result.unwrap().data, original.data);
+		}
+	}
+
+	#[test]
+	fn record_reader_errors_when_log_is_truncated_before_end_offset() {
+		let mut header = sample_header();
+		let records = vec![typed_rec(
+			super::UninstallRecTyp::DeleteFile,
+			vec![super::write_unicode_string("C:\\Old\\file.txt")],
+			vec![],
+		)];
+
+		let mut buf = Vec::new();
+		super::write_log(&mut header, &records, &mut buf).unwrap();
+		buf.truncate(buf.len() - 4); // drop trailing bytes without updating end_offset
+
+		let mut cursor = std::io::Cursor::new(buf);
+		let read_back = super::Header::from_reader(&mut cursor).unwrap();
+
+		let results: Vec<_> = super::RecordReader::new(cursor, &read_back).unwrap().collect();
+		assert!(results.last().unwrap().is_err());
 	}
 }